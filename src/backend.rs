@@ -0,0 +1,167 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpStream};
+use std::str::FromStr;
+
+
+/// Which parse server protocol to speak: the bespoke length-prefixed TCP
+/// protocol, or a standard Parsoid/RESTBase-style HTTP REST API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Backend {
+    Tcp,
+    Http,
+}
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Backend::Tcp),
+            "http" => Ok(Backend::Http),
+            other => Err(format!("unknown backend \"{}\" (expected \"tcp\" or \"http\")", other)),
+        }
+    }
+}
+impl Default for Backend {
+    fn default() -> Self { Backend::Tcp }
+}
+
+
+#[derive(Debug)]
+pub(crate) enum BackendError {
+    Io(io::Error),
+    Http { status: u16, body: String },
+    MissingParseUrl,
+}
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error talking to parse server: {}", e),
+            Self::Http { status, body } => write!(f, "parse server returned HTTP {}: {}", status, body),
+            Self::MissingParseUrl => write!(f, "--parse-url is required when --backend=http"),
+        }
+    }
+}
+impl std::error::Error for BackendError {
+}
+impl From<io::Error> for BackendError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+
+/// Speaks the "WiKiCrUnCh" length-prefixed binary protocol to a local parse
+/// server listening on `server_port`.
+pub(crate) fn talk_to_server_tcp(server_port: u16, title: &str, wikitext: &str) -> Result<String, BackendError> {
+    // open socket
+    let mut stream = TcpStream::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), server_port))?;
+
+    // write magic
+    stream.write_all(b"WiKiCrUnCh")?;
+
+    // write title length
+    let title_len_i32: i32 = title.len()
+        .try_into().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "title length does not fit into 32-bit integer"))?;
+    let title_len_bytes = title_len_i32.to_be_bytes();
+    stream.write_all(&title_len_bytes)?;
+
+    // write title
+    stream.write_all(title.as_bytes())?;
+
+    // write length
+    let wikitext_len_i32: i32 = wikitext.len()
+        .try_into().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "wikitext length does not fit into 32-bit integer"))?;
+    let wikitext_len_bytes = wikitext_len_i32.to_be_bytes();
+    stream.write_all(&wikitext_len_bytes)?;
+
+    // write text
+    stream.write_all(wikitext.as_bytes())?;
+
+    // read length
+    let mut html_len_bytes = [0u8; 4];
+    stream.read_exact(&mut html_len_bytes)?;
+    let html_len_i32 = i32::from_be_bytes(
+        html_len_bytes.try_into().expect("failed to store HTML length as 32-bit integer")
+    );
+    let html_len_usize: usize = html_len_i32.try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "server sent a negative HTML length"))?;
+
+    // read as many bytes
+    let mut html_bytes = vec![0u8; html_len_usize];
+    stream.read_exact(&mut html_bytes)?;
+
+    // try to turn it into a string
+    let string = String::from_utf8(html_bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    // send our goodbyes
+    stream.write_all(b"EnOuGhWiKi")?;
+
+    Ok(string)
+}
+
+
+/// Percent-encodes a single path segment (here: the page title) for use in
+/// a REST API URL.
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            },
+            _ => {
+                encoded.push_str(&format!("%{:02X}", byte));
+            },
+        }
+    }
+    encoded
+}
+
+
+/// POSTs `wikitext` to a Parsoid/RESTBase-style `transform/wikitext/to/html`
+/// REST endpoint rooted at `parse_url` and returns the rendered XHTML.
+pub(crate) fn talk_to_server_http(parse_url: &str, title: &str, wikitext: &str) -> Result<String, BackendError> {
+    let url = format!(
+        "{}/transform/wikitext/to/html/{}",
+        parse_url.trim_end_matches('/'),
+        encode_path_segment(title),
+    );
+    let response = ureq::post(&url).send_string(wikitext);
+
+    match response {
+        Ok(resp) => {
+            resp.into_string()
+                .map_err(|e| BackendError::Io(e))
+        },
+        Err(ureq::Error::Status(status, resp)) => {
+            let body = resp.into_string().unwrap_or_default();
+            Err(BackendError::Http { status, body })
+        },
+        Err(ureq::Error::Transport(transport)) => {
+            Err(BackendError::Io(io::Error::new(io::ErrorKind::Other, transport.to_string())))
+        },
+    }
+}
+
+
+/// Dispatches to the configured backend. Called by every worker in the
+/// parsing pool; each call opens its own connection (a TCP stream, or an
+/// HTTP request).
+pub(crate) fn talk_to_server(
+    backend: Backend,
+    parse_server_port: u16,
+    parse_url: Option<&str>,
+    title: &str,
+    wikitext: &str,
+) -> Result<String, BackendError> {
+    match backend {
+        Backend::Tcp => talk_to_server_tcp(parse_server_port, title, wikitext),
+        Backend::Http => {
+            let parse_url = parse_url.ok_or(BackendError::MissingParseUrl)?;
+            talk_to_server_http(parse_url, title, wikitext)
+        },
+    }
+}
@@ -2,11 +2,15 @@ use std::path::PathBuf;
 
 use clap::Clap;
 
+use crate::backend::Backend;
+
 
 #[derive(Clap)]
 pub(crate) struct Opts {
     pub parse_server_port: u16,
-    pub wiki_xml: PathBuf,
+    /// Path to a (optionally `.bz2`/`.gz`-compressed) dump file, or an
+    /// `http(s)://` URL to stream one from.
+    pub wiki_xml: String,
     pub output_file: Option<PathBuf>,
     pub title: Option<String>,
     #[clap(long, short)]
@@ -15,4 +19,17 @@ pub(crate) struct Opts {
     pub xhtml_output: bool,
     #[clap(long, short)]
     pub no_plain_output: bool,
+    #[clap(long, default_value = "tcp")]
+    pub backend: Backend,
+    #[clap(long)]
+    pub parse_url: Option<String>,
+    /// Number of worker threads parsing pages concurrently, each with its
+    /// own server connection.
+    #[clap(long, short, default_value = "1")]
+    pub jobs: usize,
+    /// Also emit structured Markdown (headings, paragraphs, list items) for
+    /// each page, after the whitespace-collapsed plain-text line (which
+    /// `--no-plain-output` turns off).
+    #[clap(long)]
+    pub markdown: bool,
 }
@@ -0,0 +1,27 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+
+
+/// Opens a dump source for streaming, decompressing `.bz2`/`.gz` on the fly
+/// and fetching `http(s)://` URLs over HTTP rather than requiring a local,
+/// already-decompressed file. `source` is the raw `wiki_xml` CLI argument.
+pub(crate) fn open_dump_source(source: &str) -> Result<Box<dyn Read>, io::Error> {
+    let raw_reader: Box<dyn Read> = if source.starts_with("http://") || source.starts_with("https://") {
+        let response = ureq::get(source).call()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        Box::new(response.into_reader())
+    } else {
+        Box::new(File::open(source)?)
+    };
+
+    if source.ends_with(".bz2") {
+        Ok(Box::new(BzDecoder::new(raw_reader)))
+    } else if source.ends_with(".gz") {
+        Ok(Box::new(GzDecoder::new(raw_reader)))
+    } else {
+        Ok(raw_reader)
+    }
+}
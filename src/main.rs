@@ -1,66 +1,21 @@
+mod backend;
 mod cli;
+mod input;
+mod namespaces;
 mod parsing;
+mod pool;
 
 
-use std::convert::TryInto;
-use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
-use std::net::{Ipv4Addr, SocketAddrV4, TcpStream};
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
 
 use clap::Clap;
 use env_logger;
-use xml::reader::{EventReader, XmlEvent};
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
 
 use crate::cli::Opts;
-use crate::parsing::xhtml_to_plain;
-
-
-fn talk_to_server(server_port: u16, title: &str, wikitext: &str) -> Result<String, io::Error> {
-    // open socket
-    let mut stream = TcpStream::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), server_port))?;
-
-    // write magic
-    stream.write_all(b"WiKiCrUnCh")?;
-
-    // write title length
-    let title_len_i32: i32 = title.len()
-        .try_into().expect("failed to store title length as 32-bit integer");
-    let title_len_bytes = title_len_i32.to_be_bytes();
-    stream.write_all(&title_len_bytes)?;
-
-    // write title
-    stream.write_all(title.as_bytes())?;
-
-    // write length
-    let wikitext_len_i32: i32 = wikitext.len()
-        .try_into().expect("failed to store wikitext length as 32-bit integer");
-    let wikitext_len_bytes = wikitext_len_i32.to_be_bytes();
-    stream.write_all(&wikitext_len_bytes)?;
-
-    // write text
-    stream.write_all(wikitext.as_bytes())?;
-
-    // read length
-    let mut html_len_bytes = [0u8; 4];
-    stream.read_exact(&mut html_len_bytes)?;
-    let html_len_i32 = i32::from_be_bytes(
-        html_len_bytes.try_into().expect("failed to store HTML length as 32-bit integer")
-    );
-    let html_len_usize: usize = html_len_i32.try_into().expect("failed to store HTML length as usize");
-
-    // read as many bytes
-    let mut html_bytes = vec![0u8; html_len_usize];
-    stream.read_exact(&mut html_bytes)?;
-
-    // try to turn it into a string
-    let string = String::from_utf8(html_bytes)
-        .expect("failed to parse HTML as UTF-8");
-
-    // send our goodbyes
-    stream.write_all(b"EnOuGhWiKi")?;
-
-    Ok(string)
-}
+use crate::namespaces::{category_namespace_name, is_skippable_title};
+use crate::pool::{Job, ServerConfig, WorkerPool};
 
 
 macro_rules! output_line {
@@ -73,6 +28,14 @@ macro_rules! output_line {
     };
 }
 
+macro_rules! output_job {
+    ($file:expr, $job_output:expr) => {
+        for line in &$job_output.lines {
+            output_line!($file, "{}", line);
+        }
+    };
+}
+
 
 fn main() {
     env_logger::init();
@@ -88,13 +51,20 @@ fn main() {
     let text_level = vec![
         "mediawiki", "page", "revision", "text",
     ];
+    let namespace_level = vec![
+        "mediawiki", "siteinfo", "namespaces", "namespace",
+    ];
 
     {
-        let file = File::open(&opts.wiki_xml).unwrap();
-        let mut out_file = opts.output_file.map(|f| File::create(f).expect("failed to open output file"));
-        let reader = BufReader::new(file);
-
-        let parser = EventReader::new(reader);
+        let dump_source = input::open_dump_source(&opts.wiki_xml).unwrap();
+        let mut out_file = opts.output_file.map(|f| std::fs::File::create(f).expect("failed to open output file"));
+        let reader = BufReader::new(dump_source);
+
+        let parser_config = ParserConfig::new()
+            .cdata_to_characters(true)
+            .coalesce_characters(true)
+            .whitespace_to_characters(true);
+        let parser = EventReader::new_with_config(reader, parser_config);
         let mut element_stack: Vec<String> = Vec::new();
 
         let mut page_count: usize = 0;
@@ -102,36 +72,62 @@ fn main() {
         let mut store_text = false;
         let mut current_title: Option<String> = None;
         let mut keep_going = false;
+        let mut namespace_name_to_key: HashMap<String, i32> = HashMap::new();
+        let mut current_namespace_key: Option<i32> = None;
+        let mut category_namespace = "Category".to_owned();
+        let mut category_namespace_resolved = false;
+        let mut pool: Option<WorkerPool> = None;
+        let mut next_job_index: usize = 0;
 
         for event_res in parser {
             let event = event_res.unwrap();
 
             match event {
-                XmlEvent::StartElement { name, .. } => {
+                XmlEvent::StartElement { name, attributes, .. } => {
                     element_stack.push(name.local_name.clone());
                     if element_stack == page_level {
                         page_count += 1;
                         current_title = None;
+
+                        if !category_namespace_resolved {
+                            // the <siteinfo> block is done; resolve the localized category name once
+                            category_namespace = category_namespace_name(&namespace_name_to_key);
+                            category_namespace_resolved = true;
+
+                            pool = Some(WorkerPool::new(opts.jobs, ServerConfig {
+                                backend: opts.backend,
+                                parse_server_port: opts.parse_server_port,
+                                parse_url: opts.parse_url.clone(),
+                                xhtml_output: opts.xhtml_output,
+                                want_plain_output: !opts.no_plain_output,
+                                want_markdown_output: opts.markdown,
+                                category_namespace: category_namespace.clone(),
+                            }));
+                        }
                     } else if element_stack == text_level || element_stack == title_level {
                         text.clear();
                         store_text = true;
+                    } else if element_stack == namespace_level {
+                        current_namespace_key = attributes.iter()
+                            .find(|attr| attr.name.local_name == "key")
+                            .and_then(|attr| attr.value.parse().ok());
+                        text.clear();
+                        store_text = true;
                     }
                 },
                 XmlEvent::EndElement { .. } => {
                     if store_text {
                         store_text = false;
 
-                        if element_stack == text_level {
+                        if element_stack == namespace_level {
+                            if let Some(key) = current_namespace_key.take() {
+                                namespace_name_to_key.insert(text.clone(), key);
+                            }
+                        } else if element_stack == text_level {
                             let mut parse_it = true;
 
                             if let Some(ct) = &current_title {
-                                if ct.starts_with("Medium:") || ct.starts_with("Spezial:")
-                                    || ct.starts_with("Diskussion:")
-                                    || ct.starts_with("Benutzer:") || ct.starts_with("Benutzer Diskussion:")
-                                    || ct.starts_with("Datei:") || ct.starts_with("Datei Diskussion:")
-                                    || ct.starts_with("Vorlage:") || ct.starts_with("Vorlage Diskussion:")
-                                    || ct.starts_with("Kategorie:") || ct.starts_with("Kategorie Diskussion:") {
-
+                                if is_skippable_title(ct, &namespace_name_to_key) {
                                     parse_it = false;
                                 }
                             }
@@ -151,20 +147,15 @@ fn main() {
                             if parse_it {
                                 let page_title = current_title
                                     .as_deref()
-                                    .unwrap_or("Unbekannte Seite");
-                                let xhtml = talk_to_server(opts.parse_server_port, page_title, &text)
-                                    .unwrap();
+                                    .unwrap_or("(untitled page)")
+                                    .to_owned();
 
-                                if xhtml.len() > 0 {
-                                    if opts.xhtml_output {
-                                        output_line!(out_file, "{}", xhtml);
-                                    }
+                                pool.as_ref().expect("worker pool set up before first page")
+                                    .submit(Job { job_index: next_job_index, title: page_title, wikitext: text.clone() });
+                                next_job_index += 1;
 
-                                    if !opts.no_plain_output {
-                                        let plaintext = xhtml_to_plain(&xhtml)
-                                            .unwrap();
-                                        output_line!(out_file, "{}", plaintext);
-                                    }
+                                for job_output in pool.as_mut().unwrap().poll_ready() {
+                                    output_job!(out_file, job_output);
                                 }
 
                                 if opts.and_after {
@@ -188,7 +179,21 @@ fn main() {
                                 true
                             };
                             if output_title {
-                                output_line!(out_file, "# {} TITLE: {}", page_count, text);
+                                // reserve this page's slot in the same order-preserving
+                                // queue the worker pool uses for page content, so the
+                                // title line can never race ahead of (or fall behind)
+                                // the body it belongs to
+                                let job_index = next_job_index;
+                                next_job_index += 1;
+
+                                let title_pool = pool.as_mut()
+                                    .expect("worker pool set up before first page");
+                                title_pool.submit_immediate(job_index, vec![
+                                    format!("# {} TITLE: {}", page_count, text),
+                                ]);
+                                for job_output in title_pool.poll_ready() {
+                                    output_job!(out_file, job_output);
+                                }
                             }
                             current_title = Some(text.clone());
                         }
@@ -200,11 +205,14 @@ fn main() {
                         text.push_str(&chars);
                     }
                 },
-                XmlEvent::CData(_) => {
-                    panic!("CDATA!");
-                },
                 _ => {},
             }
         }
+
+        if let Some(pool) = pool {
+            for job_output in pool.finish() {
+                output_job!(out_file, job_output);
+            }
+        }
     }
 }
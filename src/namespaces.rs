@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+
+/// Namespace keys that MediaWiki reserves for non-article content: media
+/// descriptions, special pages, talk pages, user pages, files, templates
+/// and categories (plus their talk pages). Dumps list the localized name
+/// for each of these under `<siteinfo><namespaces>`; anything whose title
+/// prefix resolves to one of these keys is not encyclopedic article text.
+const NON_ARTICLE_NAMESPACE_KEYS: &[i32] = &[-2, -1, 1, 2, 3, 6, 7, 10, 11, 14, 15];
+
+/// Namespace key MediaWiki reserves for categories.
+pub(crate) const CATEGORY_NAMESPACE_KEY: i32 = 14;
+
+
+/// Returns whether `title` belongs to a non-article namespace, as resolved
+/// via the localized namespace names parsed from the dump's `<siteinfo>`.
+pub(crate) fn is_skippable_title(title: &str, namespace_name_to_key: &HashMap<String, i32>) -> bool {
+    let prefix = match title.find(':') {
+        Some(colon_index) => &title[..colon_index],
+        None => return false,
+    };
+
+    match namespace_name_to_key.get(prefix) {
+        Some(key) => NON_ARTICLE_NAMESPACE_KEYS.contains(key),
+        None => false,
+    }
+}
+
+
+/// Finds the localized name of the Category namespace among the parsed
+/// namespace names, falling back to the English default if the dump is
+/// somehow missing it.
+pub(crate) fn category_namespace_name(namespace_name_to_key: &HashMap<String, i32>) -> String {
+    namespace_name_to_key.iter()
+        .find(|(_name, &key)| key == CATEGORY_NAMESPACE_KEY)
+        .map(|(name, _key)| name.clone())
+        .unwrap_or_else(|| "Category".to_owned())
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use kuchiki;
+use kuchiki::traits::TendrilSink;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use sxd_document;
@@ -80,72 +82,133 @@ fn to_element<'a>(val: sxd_xpath::Value<'a>) -> Result<Element<'a>, ParsingError
 }
 
 
-fn iterate_element(element: &Element) -> String {
+/// Abstracts over the handful of DOM operations `iterate_node` needs so it
+/// can walk either an `sxd_document` tree (strict XML) or a `kuchiki` tree
+/// (lenient HTML5) with the same text-extraction logic.
+trait DomNode: Sized {
+    fn element_name(&self) -> Option<String>;
+    fn attribute_value(&self, name: &str) -> Option<String>;
+    fn text(&self) -> Option<String>;
+    fn children(&self) -> Vec<Self>;
+}
+
+
+#[derive(Clone, Copy)]
+enum SxdNode<'a> {
+    Element(Element<'a>),
+    Text(sxd_document::dom::Text<'a>),
+}
+impl<'a> DomNode for SxdNode<'a> {
+    fn element_name(&self) -> Option<String> {
+        match self {
+            Self::Element(e) => Some(e.name().local_part().to_owned()),
+            Self::Text(_) => None,
+        }
+    }
+    fn attribute_value(&self, name: &str) -> Option<String> {
+        match self {
+            Self::Element(e) => e.attribute_value(name).map(|v| v.to_owned()),
+            Self::Text(_) => None,
+        }
+    }
+    fn text(&self) -> Option<String> {
+        match self {
+            Self::Element(_) => None,
+            Self::Text(t) => Some(t.text().to_owned()),
+        }
+    }
+    fn children(&self) -> Vec<Self> {
+        match self {
+            Self::Element(e) => e.children().iter().filter_map(|c| match c {
+                ChildOfElement::Element(el) => Some(SxdNode::Element(*el)),
+                ChildOfElement::Text(t) => Some(SxdNode::Text(*t)),
+                ChildOfElement::Comment(_) => None,
+                ChildOfElement::ProcessingInstruction(_) => None,
+            }).collect(),
+            Self::Text(_) => Vec::new(),
+        }
+    }
+}
+
+
+impl DomNode for kuchiki::NodeRef {
+    fn element_name(&self) -> Option<String> {
+        self.as_element().map(|e| e.name.local.to_string())
+    }
+    fn attribute_value(&self, name: &str) -> Option<String> {
+        self.as_element()
+            .and_then(|e| e.attributes.borrow().get(name).map(|v| v.to_owned()))
+    }
+    fn text(&self) -> Option<String> {
+        self.as_text().map(|t| t.borrow().clone())
+    }
+    fn children(&self) -> Vec<Self> {
+        self.children().collect()
+    }
+}
+
+
+fn iterate_node<N: DomNode>(node: &N, category_namespace: &str) -> String {
     let mut ret = String::new();
+    let category_href_prefix = format!("./{}:", category_namespace);
+
+    for child in node.children() {
+        if let Some(elem_name) = child.element_name() {
+            if elem_name.len() == 2 && elem_name.starts_with("h") && elem_name.chars().nth(1).unwrap().is_ascii_digit() {
+                // <h1> etc. don't tend to contain full sentences
+                // => skip
+            } else {
+                match elem_name.to_lowercase().as_str() {
+                    "ul"|"ol"|"dl"|"li" => {
+                        // lists tend to contain fragments instead of sentences
+                        // => skip
+                    },
+                    "math"|"chem"|"timeline"|"syntaxhighlight"|"hiero"|"inputbox"|"score"|"graph"|"categorytree" => {
+                        // this is anything but the natural language we're trying to process
+                        // => skip
+                    },
+                    "sup"|"sub" => {
+                        // mostly references, and otherwise not very natural-language either
+                        // => skip
+                    },
+                    "a" => {
+                        // ensure it is not a category link
+                        let descend = if let Some(href) = child.attribute_value("href") {
+                            !href.starts_with(&category_href_prefix)
+                        } else {
+                            true
+                        };
 
-    for child in &element.children() {
-        match child {
-            ChildOfElement::Element(elem) => {
-                let elem_name = elem.name().local_part();
-                if elem_name.len() == 2 && elem_name.starts_with("h") && elem_name.chars().nth(1).unwrap().is_ascii_digit() {
-                    // <h1> etc. don't tend to contain full sentences
-                    // => skip
-                } else {
-                    match elem.name().local_part().to_lowercase().as_str() {
-                        "ul"|"ol"|"dl"|"li" => {
-                            // lists tend to contain fragments instead of sentences
-                            // => skip
-                        },
-                        "math"|"chem"|"timeline"|"syntaxhighlight"|"hiero"|"inputbox"|"score"|"graph"|"categorytree" => {
-                            // this is anything but the natural language we're trying to process
-                            // => skip
-                        },
-                        "sup"|"sub" => {
-                            // mostly references, and otherwise not very natural-language either
-                            // => skip
-                        },
-                        "a" => {
-                            // ensure it is not a category link
-                            let descend = if let Some(href) = elem.attribute_value("href") {
-                                !href.starts_with("./Kategorie:")
-                            } else {
-                                true
-                            };
-
-                            if descend {
-                                let children_string = iterate_element(elem);
-                                ret.push_str(&children_string);
-                            }
-                        },
-                        "table" => {
-                            // tables can contain both sentences and fragmentary text
-                            // => skip
-                        },
-                        "span" => {
-                            // ensure it's not an image
-                            let descend = if let Some(type_of) = elem.attribute_value("typeof") {
-                                !type_of.contains("mw:Image")
-                            } else {
-                                true
-                            };
-
-                            if descend {
-                                let children_string = iterate_element(elem);
-                                ret.push_str(&children_string);
-                            }
-                        },
-                        _other => {
-                            let children_string = iterate_element(elem);
+                        if descend {
+                            let children_string = iterate_node(&child, category_namespace);
                             ret.push_str(&children_string);
-                        },
-                    }
+                        }
+                    },
+                    "table" => {
+                        // tables can contain both sentences and fragmentary text
+                        // => skip
+                    },
+                    "span" => {
+                        // ensure it's not an image
+                        let descend = if let Some(type_of) = child.attribute_value("typeof") {
+                            !type_of.contains("mw:Image")
+                        } else {
+                            true
+                        };
+
+                        if descend {
+                            let children_string = iterate_node(&child, category_namespace);
+                            ret.push_str(&children_string);
+                        }
+                    },
+                    _other => {
+                        let children_string = iterate_node(&child, category_namespace);
+                        ret.push_str(&children_string);
+                    },
                 }
-            },
-            ChildOfElement::Text(t) => {
-                ret.push_str(t.text());
-            },
-            ChildOfElement::Comment(_comment) => {},
-            ChildOfElement::ProcessingInstruction(_instr) => {},
+            }
+        } else if let Some(t) = child.text() {
+            ret.push_str(&t);
         }
     }
 
@@ -153,28 +216,180 @@ fn iterate_element(element: &Element) -> String {
 }
 
 
-pub(crate) fn xhtml_to_plain(mut xhtml: &str) -> Result<String, ParsingError> {
-    if xhtml.starts_with("<!DOCTYPE html>") {
-        xhtml = &xhtml["<!DOCTYPE html>".len()..];
-    }
-    let xhtml_replaced = xhtml
-        .replace(" xmlns=\"http://www.w3.org/2000/xmlns/\"", "")
-        .replace(" xmlns='http://www.w3.org/2000/xmlns/'", "");
-
-    // parse
-    let parsed = sxd_document::parser::parse(&xhtml_replaced)?;
+/// Parses `xhtml` strictly and hands the `<body>` element, as an
+/// `sxd_document` node, to `body_fn`.
+fn with_strict_body<T, F: FnOnce(&SxdNode) -> T>(xhtml: &str, body_fn: F) -> Result<T, ParsingError> {
+    let parsed = sxd_document::parser::parse(xhtml)?;
     let document = parsed.as_document();
     let xpath_factory = sxd_xpath::Factory::new();
     let xpath_context = sxd_xpath::Context::new();
 
-    // get body
     let body_xpath = xpath_factory.build("/html/body")?
         .expect("XPath actually generated");
     let body_element = to_element(body_xpath.evaluate(&xpath_context, document.root())?)?;
 
-    let mut plaintext = iterate_element(&body_element);
-    plaintext = WHITESPACE_RE.replace_all(&plaintext, " ")
-        .trim()
-        .to_owned();
-    Ok(plaintext)
+    Ok(body_fn(&SxdNode::Element(body_element)))
+}
+
+
+/// Parses `xhtml` leniently as HTML5 and hands the `<body>` element, as a
+/// `kuchiki` node, to `body_fn`.
+fn with_lenient_body<T, F: FnOnce(&kuchiki::NodeRef) -> T>(xhtml: &str, body_fn: F) -> Result<T, ParsingError> {
+    let document = kuchiki::parse_html().one(xhtml);
+    let body = document.select_first("body")
+        .map_err(|_| ParsingError::ExpectedElement("body".to_owned()))?;
+
+    Ok(body_fn(&body.as_node().clone()))
+}
+
+
+/// Strips the leading doctype and the `xmlns` attribute hack that otherwise
+/// trips up `sxd_document`'s strict parsing.
+fn normalize_xhtml(mut xhtml: &str) -> String {
+    if xhtml.starts_with("<!DOCTYPE html>") {
+        xhtml = &xhtml["<!DOCTYPE html>".len()..];
+    }
+    xhtml
+        .replace(" xmlns=\"http://www.w3.org/2000/xmlns/\"", "")
+        .replace(" xmlns='http://www.w3.org/2000/xmlns/'", "")
+}
+
+
+pub(crate) fn xhtml_to_plain(xhtml: &str, category_namespace: &str) -> Result<String, ParsingError> {
+    let xhtml_replaced = normalize_xhtml(xhtml);
+
+    // sxd_document demands well-formed XML; if the server's output doesn't
+    // satisfy that (stray unescaped entities, void elements, ...), fall
+    // back to a lenient HTML5 parse rather than failing the whole page
+    let plaintext = match with_strict_body(&xhtml_replaced, |body| iterate_node(body, category_namespace)) {
+        Ok(text) => text,
+        Err(ParsingError::XmlParsing(_)) => with_lenient_body(&xhtml_replaced, |body| iterate_node(body, category_namespace))?,
+        Err(e) => return Err(e),
+    };
+    Ok(WHITESPACE_RE.replace_all(&plaintext, " ").trim().to_owned())
+}
+
+
+/// Walks the body DOM like `iterate_node`, but instead of flattening
+/// everything into one line, emits `#`-prefixed headings, blank-line
+/// separated paragraphs, and `- `/`1. ` list items -- the structure
+/// downstream corpus builders and diff tools often want to keep.
+fn walk_markdown<N: DomNode>(node: &N, category_namespace: &str, out: &mut String) {
+    for child in node.children() {
+        let elem_name = match child.element_name() {
+            Some(n) => n.to_lowercase(),
+            None => continue,
+        };
+
+        if elem_name.len() == 2 && elem_name.starts_with('h') && elem_name.as_bytes()[1].is_ascii_digit() {
+            let level = (elem_name.as_bytes()[1] - b'0') as usize;
+            let heading = WHITESPACE_RE.replace_all(&iterate_node(&child, category_namespace), " ")
+                .trim()
+                .to_owned();
+            if !heading.is_empty() {
+                out.push_str(&"#".repeat(level));
+                out.push(' ');
+                out.push_str(&heading);
+                out.push_str("\n\n");
+            }
+            continue;
+        }
+
+        match elem_name.as_str() {
+            "math"|"chem"|"timeline"|"syntaxhighlight"|"hiero"|"inputbox"|"score"|"graph"|"categorytree" => {
+                // this is anything but the natural language we're trying to process
+                // => skip
+            },
+            "sup"|"sub" => {
+                // mostly references, and otherwise not very natural-language either
+                // => skip
+            },
+            "table" => {
+                // tables can contain both sentences and fragmentary text
+                // => skip
+            },
+            "p" => {
+                let paragraph = WHITESPACE_RE.replace_all(&iterate_node(&child, category_namespace), " ")
+                    .trim()
+                    .to_owned();
+                if !paragraph.is_empty() {
+                    out.push_str(&paragraph);
+                    out.push_str("\n\n");
+                }
+            },
+            "ul" => write_list_markdown(&child, category_namespace, out, None),
+            "ol" => write_list_markdown(&child, category_namespace, out, Some(1)),
+            "a" => {
+                let category_href_prefix = format!("./{}:", category_namespace);
+                let descend = match child.attribute_value("href") {
+                    Some(href) => !href.starts_with(&category_href_prefix),
+                    None => true,
+                };
+                if descend {
+                    walk_markdown(&child, category_namespace, out);
+                }
+            },
+            "span" => {
+                let descend = match child.attribute_value("typeof") {
+                    Some(type_of) => !type_of.contains("mw:Image"),
+                    None => true,
+                };
+                if descend {
+                    walk_markdown(&child, category_namespace, out);
+                }
+            },
+            _other => {
+                walk_markdown(&child, category_namespace, out);
+            },
+        }
+    }
+}
+
+
+/// Emits one `- ` (unordered, `ordinal` is `None`) or `1. ` (ordered, counting
+/// up from `ordinal`) line per direct `<li>` child of a `<ul>`/`<ol>`.
+fn write_list_markdown<N: DomNode>(list_node: &N, category_namespace: &str, out: &mut String, mut ordinal: Option<usize>) {
+    for child in list_node.children() {
+        if child.element_name().as_deref().map(str::to_lowercase).as_deref() != Some("li") {
+            continue;
+        }
+
+        let item = WHITESPACE_RE.replace_all(&iterate_node(&child, category_namespace), " ")
+            .trim()
+            .to_owned();
+        if item.is_empty() {
+            continue;
+        }
+
+        match ordinal {
+            Some(n) => {
+                out.push_str(&format!("{}. ", n));
+                ordinal = Some(n + 1);
+            },
+            None => out.push_str("- "),
+        }
+        out.push_str(&item);
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
+
+pub(crate) fn xhtml_to_markdown(xhtml: &str, category_namespace: &str) -> Result<String, ParsingError> {
+    let xhtml_replaced = normalize_xhtml(xhtml);
+
+    let markdown = match with_strict_body(&xhtml_replaced, |body| {
+        let mut out = String::new();
+        walk_markdown(body, category_namespace, &mut out);
+        out
+    }) {
+        Ok(text) => text,
+        Err(ParsingError::XmlParsing(_)) => with_lenient_body(&xhtml_replaced, |body| {
+            let mut out = String::new();
+            walk_markdown(body, category_namespace, &mut out);
+            out
+        })?,
+        Err(e) => return Err(e),
+    };
+    Ok(markdown.trim().to_owned())
 }
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use log::warn;
+
+use crate::backend::{talk_to_server, Backend};
+use crate::parsing::{xhtml_to_markdown, xhtml_to_plain};
+
+
+/// Everything a worker needs to parse a page on its own connection,
+/// independent of the main thread's `Opts`.
+#[derive(Clone)]
+pub(crate) struct ServerConfig {
+    pub(crate) backend: Backend,
+    pub(crate) parse_server_port: u16,
+    pub(crate) parse_url: Option<String>,
+    pub(crate) xhtml_output: bool,
+    pub(crate) want_plain_output: bool,
+    pub(crate) want_markdown_output: bool,
+    pub(crate) category_namespace: String,
+}
+
+
+pub(crate) struct Job {
+    pub(crate) job_index: usize,
+    pub(crate) title: String,
+    pub(crate) wikitext: String,
+}
+
+
+struct RawResult {
+    job_index: usize,
+    lines: Vec<String>,
+}
+
+
+/// One finished page, ready to be written in order.
+pub(crate) struct JobOutput {
+    pub(crate) lines: Vec<String>,
+}
+
+
+/// A bounded pool of worker threads, each holding its own server
+/// connection, that parses submitted pages concurrently. Results are
+/// buffered by `job_index` so callers can pull them back out in the order
+/// the jobs were submitted, even though workers finish out of order.
+pub(crate) struct WorkerPool {
+    job_tx: Option<mpsc::Sender<Job>>,
+    result_rx: mpsc::Receiver<RawResult>,
+    workers: Vec<thread::JoinHandle<()>>,
+    pending: BTreeMap<usize, Vec<String>>,
+    next_to_emit: usize,
+}
+impl WorkerPool {
+    pub(crate) fn new(worker_count: usize, config: ServerConfig) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (result_tx, result_rx) = mpsc::channel::<RawResult>();
+
+        let workers = (0..worker_count.max(1)).map(|_| {
+            let job_rx = Arc::clone(&job_rx);
+            let result_tx = result_tx.clone();
+            let config = config.clone();
+
+            thread::spawn(move || {
+                loop {
+                    let job = {
+                        let rx = job_rx.lock().expect("job queue poisoned");
+                        rx.recv()
+                    };
+                    let job = match job {
+                        Ok(j) => j,
+                        Err(_) => break,
+                    };
+
+                    let lines = process_job(&config, &job);
+                    if result_tx.send(RawResult { job_index: job.job_index, lines }).is_err() {
+                        break;
+                    }
+                }
+            })
+        }).collect();
+
+        Self {
+            job_tx: Some(job_tx),
+            result_rx,
+            workers,
+            pending: BTreeMap::new(),
+            next_to_emit: 0,
+        }
+    }
+
+    pub(crate) fn submit(&self, job: Job) {
+        self.job_tx.as_ref().expect("worker pool is gone").send(job).expect("worker pool is gone");
+    }
+
+    /// Slots a result that was computed synchronously on the caller's
+    /// thread (e.g. a title line, which has to stay interleaved with the
+    /// page content around it) into the same ordering buffer that worker
+    /// results land in, as if a worker had produced it for `job_index`.
+    pub(crate) fn submit_immediate(&mut self, job_index: usize, lines: Vec<String>) {
+        self.pending.insert(job_index, lines);
+    }
+
+    /// Drains whatever results have arrived without blocking, returning
+    /// those that are next in line to be written.
+    pub(crate) fn poll_ready(&mut self) -> Vec<JobOutput> {
+        while let Ok(result) = self.result_rx.try_recv() {
+            self.pending.insert(result.job_index, result.lines);
+        }
+        self.drain_contiguous()
+    }
+
+    /// Stops accepting new jobs, waits for every in-flight job to finish,
+    /// and returns all remaining results in submission order.
+    pub(crate) fn finish(mut self) -> Vec<JobOutput> {
+        // dropping the sender hangs up the channel once the queue drains,
+        // which is how idle workers notice there is nothing left to do
+        self.job_tx.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+
+        while let Ok(result) = self.result_rx.recv() {
+            self.pending.insert(result.job_index, result.lines);
+        }
+
+        let mut ret = self.drain_contiguous();
+        ret.extend(
+            std::mem::take(&mut self.pending).into_values()
+                .map(|lines| JobOutput { lines })
+        );
+        ret
+    }
+
+    fn drain_contiguous(&mut self) -> Vec<JobOutput> {
+        let mut ret = Vec::new();
+        while let Some(lines) = self.pending.remove(&self.next_to_emit) {
+            ret.push(JobOutput { lines });
+            self.next_to_emit += 1;
+        }
+        ret
+    }
+}
+
+
+/// Parses one page and renders it into the requested output formats. Never
+/// panics: a server hiccup or a malformed page only drops that one page's
+/// output (with a warning), instead of taking down the worker thread and
+/// leaving its `job_index` permanently missing from the ordering buffer.
+fn process_job(config: &ServerConfig, job: &Job) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let xhtml = match talk_to_server(
+        config.backend, config.parse_server_port, config.parse_url.as_deref(),
+        &job.title, &job.wikitext,
+    ) {
+        Ok(xhtml) => xhtml,
+        Err(e) => {
+            warn!("failed to parse \"{}\": {}", job.title, e);
+            return lines;
+        },
+    };
+
+    if xhtml.len() > 0 {
+        if config.xhtml_output {
+            lines.push(xhtml.clone());
+        }
+
+        if config.want_plain_output {
+            match xhtml_to_plain(&xhtml, &config.category_namespace) {
+                Ok(plaintext) => lines.push(plaintext),
+                Err(e) => warn!("failed to render \"{}\" as plain text: {}", job.title, e),
+            }
+        }
+
+        if config.want_markdown_output {
+            match xhtml_to_markdown(&xhtml, &config.category_namespace) {
+                Ok(markdown) => lines.push(markdown),
+                Err(e) => warn!("failed to render \"{}\" as Markdown: {}", job.title, e),
+            }
+        }
+    }
+
+    lines
+}